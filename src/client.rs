@@ -0,0 +1,52 @@
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+
+use crate::common::{read_frame, write_frame, Request, Response};
+use crate::{KvsError, Result};
+
+///与`KvsServer`通信的客户端。`KvsServer`每个连接只处理一个请求就关闭
+///（参见`server`模块），所以这里不持有一条常驻连接，而是记住服务端地址，
+///每次调用`set`/`get`/`remove`时都重新拨号——这样同一个`KvsClient`可以
+///安全地连续调用任意多次，不会在第二次调用时撞上对端已经关闭的连接
+pub struct KvsClient {
+    addr: SocketAddr,
+}
+
+impl KvsClient {
+    ///连接到`addr`上的kvs-server，校验一次地址真的可以连通
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<KvsClient> {
+        let stream = TcpStream::connect(addr)?;
+        let addr = stream.peer_addr()?;
+        Ok(KvsClient { addr })
+    }
+
+    ///为一次请求/响应重新拨号，写入请求帧并读回响应帧
+    fn request(&self, req: &Request) -> Result<Response> {
+        let mut stream = TcpStream::connect(self.addr)?;
+        write_frame(&mut stream, req)?;
+        read_frame(&mut stream)
+    }
+
+    ///发送一次Set请求并等待响应
+    pub fn set(&self, key: String, value: String) -> Result<()> {
+        match self.request(&Request::Set { key, value })? {
+            Response::Ok(_) => Ok(()),
+            Response::Err(msg) => Err(KvsError::StringError(msg)),
+        }
+    }
+
+    ///发送一次Get请求并等待响应
+    pub fn get(&self, key: String) -> Result<Option<String>> {
+        match self.request(&Request::Get { key })? {
+            Response::Ok(value) => Ok(value),
+            Response::Err(msg) => Err(KvsError::StringError(msg)),
+        }
+    }
+
+    ///发送一次Remove请求并等待响应
+    pub fn remove(&self, key: String) -> Result<()> {
+        match self.request(&Request::Remove { key })? {
+            Response::Ok(_) => Ok(()),
+            Response::Err(msg) => Err(KvsError::StringError(msg)),
+        }
+    }
+}
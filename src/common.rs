@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+use crate::{KvsError, Result};
+
+///一帧消息体允许的最大字节数，防止对端发来一个伪造的超大长度前缀，
+///诱使我们在校验内容之前就去分配一大块内存（拒绝服务攻击）
+const MAX_FRAME_LEN: u32 = 16 * 1024 * 1024;
+
+///客户端发往服务端的请求
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Request {
+    Set { key: String, value: String },
+    Get { key: String },
+    Remove { key: String },
+}
+
+///服务端返回给客户端的响应。`Get`不存在的key返回`Ok(None)`，
+///`Set`/`Remove`成功统一返回`Ok(None)`，任何失败都会被转成字符串放进`Err`里。
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Response {
+    Ok(Option<String>),
+    Err(String),
+}
+
+///往`writer`里写入一帧：4字节大端长度前缀 + 序列化后的消息体，
+///这样对端在读取时不需要分隔符就能确定消息的边界。
+pub fn write_frame<W: Write, T: Serialize>(writer: &mut W, msg: &T) -> Result<()> {
+    let body = serde_json::to_vec(msg)?;
+    writer.write_all(&(body.len() as u32).to_be_bytes())?;
+    writer.write_all(&body)?;
+    writer.flush()?;
+    Ok(())
+}
+
+///从`reader`里读取一帧，先读4字节长度前缀，再读取对应长度的消息体并反序列化
+pub fn read_frame<R: Read, T: for<'de> Deserialize<'de>>(reader: &mut R) -> Result<T> {
+    let mut len_buf = [0u8; 4];
+    reader.read_exact(&mut len_buf)?;
+    let len = u32::from_be_bytes(len_buf);
+    if len > MAX_FRAME_LEN {
+        return Err(KvsError::StringError(format!(
+            "frame length {} exceeds max allowed {}",
+            len, MAX_FRAME_LEN
+        )));
+    }
+    let len = len as usize;
+
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body)?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn write_then_read_frame_round_trips() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, &Request::Get { key: "key".to_string() }).unwrap();
+
+        let mut cursor = Cursor::new(buf);
+        match read_frame(&mut cursor).unwrap() {
+            Request::Get { key } => assert_eq!(key, "key"),
+            _ => panic!("expected a Get request"),
+        }
+    }
+
+    ///一个伪造的超大长度前缀应该在分配消息体之前就被拒绝，而不是真的
+    ///去申请一块巨大的内存
+    #[test]
+    fn read_frame_rejects_oversized_length_before_allocating() {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&(MAX_FRAME_LEN + 1).to_be_bytes());
+
+        let mut cursor = Cursor::new(buf);
+        let result: Result<Request> = read_frame(&mut cursor);
+        assert!(matches!(result, Err(KvsError::StringError(_))));
+    }
+}
@@ -0,0 +1,15 @@
+use crate::Result;
+
+///KvsEngine定义了一个可被多个线程共享的键值存储引擎应具备的能力。
+///实现者必须是可以廉价`Clone`的（内部通过`Arc`共享状态），
+///这样同一个底层数据库就能被分发给多个线程或连接使用。
+pub trait KvsEngine: Clone + Send + 'static {
+    ///设置key对应的value，如果key已存在则覆盖原来的value
+    fn set(&self, key: String, value: String) -> Result<()>;
+
+    ///根据key查询对应的value，如果key不存在则返回None
+    fn get(&self, key: String) -> Result<Option<String>>;
+
+    ///删除key，如果key不存在则返回错误
+    fn remove(&self, key: String) -> Result<()>;
+}
@@ -0,0 +1,54 @@
+use std::io;
+use std::fmt;
+
+///KvStore统一的错误类型
+#[derive(Debug)]
+pub enum KvsError {
+    ///IO错误
+    Io(io::Error),
+    ///序列化/反序列化错误
+    Serde(serde_json::Error),
+    ///查询或删除一个不存在的key
+    KeyNotFound,
+    ///日志中的命令类型与期望的不一致，通常意味着索引已损坏
+    UnexpectedCommandType,
+    ///以字符串形式携带的错误。服务端返回的错误已经被序列化为字符串，客户端没有
+    ///足够的信息重新构造出原始的错误类型，因此原样包装转发给调用者；没有专门变体
+    ///的其他错误（例如帧长度超限、线程池线程数为0）也用它表示
+    StringError(String),
+}
+
+impl fmt::Display for KvsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KvsError::Io(err) => write!(f, "IO error: {}", err),
+            KvsError::Serde(err) => write!(f, "serde error: {}", err),
+            KvsError::KeyNotFound => write!(f, "Key not found"),
+            KvsError::UnexpectedCommandType => write!(f, "Unexpected command type"),
+            KvsError::StringError(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for KvsError {}
+
+impl From<io::Error> for KvsError {
+    fn from(err: io::Error) -> KvsError {
+        KvsError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for KvsError {
+    fn from(err: serde_json::Error) -> KvsError {
+        KvsError::Serde(err)
+    }
+}
+
+impl From<String> for KvsError {
+    fn from(msg: String) -> KvsError {
+        KvsError::StringError(msg)
+    }
+}
+
+///KvStore统一的Result类型
+pub type Result<T> = std::result::Result<T, KvsError>;
@@ -1,42 +1,89 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::path::{PathBuf, Path};
-use serde::{Serialize, Deserialize};
 use std::io::{Seek, Read, BufReader, SeekFrom, Write, BufWriter};
 use std::{io, fs};
-use std::fs::{File, OpenOptions, read};
-use serde_json::Deserializer;
+use std::fs::{File, OpenOptions};
+use std::sync::{Arc, Mutex};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use crossbeam_skiplist::SkipMap;
 use std::ffi::OsStr;
 
 //调用自身模块一样需要从lib.rc获取
-use crate::{Result, KvsError};
+use crate::log_format::{read_record, write_record, LogRecord};
+use crate::{Result, KvsError, KvsEngine};
 use std::option::Option::Some;
 
-//最大一个文件中最大文件大小
-const COMPACTION_THRESHOLD: u64 = 128;
+//触发一次压缩之前，所有生成号里允许累积的死字节总数
+const DEFAULT_COMPACTION_THRESHOLD: u64 = 1024 * 1024;
+//一个生成号的日志里死字节占比超过这个比例，才会被选中参与压缩
+const DEFAULT_COMPACTION_RATIO: f64 = 0.5;
+
+///`KvStore::open`的配置项，使用builder风格链式设置后调用`open`打开数据库。
+///不设置任何选项等价于使用默认阈值。
+#[derive(Clone, Copy, Debug)]
+pub struct KvStoreConfig {
+    compaction_threshold: u64,
+    compaction_ratio: f64,
+}
 
+impl KvStoreConfig {
+    ///使用默认的阈值创建一份配置
+    pub fn new() -> KvStoreConfig {
+        KvStoreConfig {
+            compaction_threshold: DEFAULT_COMPACTION_THRESHOLD,
+            compaction_ratio: DEFAULT_COMPACTION_RATIO,
+        }
+    }
 
-//KV存储定义
-pub struct KvStore {
-    path: PathBuf,     //文件存储目录
-    //读取器修改为文件编号-读写器索引
-    readers : HashMap<u64, BufReaderWithPos<File>>,
-    //同一时间有且只有一个文件能被写入，因此写入器不变
-    writer : BufWriterWithPos<File>,
-    index : HashMap<String, CommandPos>,
-    //记录当前写入文件的序号
-    current_gen : u64,
-    //未压缩的数据大小
-    uncompacted : u64
+    ///设置触发压缩所需的死字节总数阈值
+    pub fn compaction_threshold(mut self, threshold: u64) -> KvStoreConfig {
+        self.compaction_threshold = threshold;
+        self
+    }
+
+    ///设置一个生成号被选中参与压缩所需要达到的死字节比例，取值范围`(0.0, 1.0]`
+    pub fn compaction_ratio(mut self, ratio: f64) -> KvStoreConfig {
+        self.compaction_ratio = ratio;
+        self
+    }
+
+    ///使用这份配置打开`path`对应的数据库
+    pub fn open(self, path: impl Into<PathBuf>) -> Result<KvStore> {
+        KvStore::open_with_config(path, self)
+    }
+}
+
+impl Default for KvStoreConfig {
+    fn default() -> KvStoreConfig {
+        KvStoreConfig::new()
+    }
 }
 
-//命令定义，对于写入来说有两种操作：set、rm
-#[derive(Serialize, Deserialize, Debug)]
-pub enum Command {
-    Set {key : String, value : String},
-    Remove {key : String},
+///一个生成号(gen)对应日志文件的统计信息：总字节数与其中已经"死掉"（被覆盖或
+///被删除，压缩时可以丢弃）的字节数
+#[derive(Default, Clone, Copy)]
+struct GenStat {
+    total: u64,
+    dead: u64,
+}
+
+
+///基于日志结构的KV存储。
+///索引使用`SkipMap`，一个无锁的有序并发Map，因此读取不需要拿任何锁就能查到
+///key对应的位置；每个`KvStore`克隆都拥有一份独立的读取器集合（每个读取器只属于
+///持有它的线程/克隆），互不干扰，因此并发的`get`之间基本不会相互阻塞：
+///只有为一个新的生成号打开文件句柄时，才会短暂拿一次共享的`removed_gens`锁。
+///只有写入路径仍然需要一把互斥锁，保证同一时间只有一个写入者在追加日志。
+#[derive(Clone)]
+pub struct KvStore {
+    index: Arc<SkipMap<String, CommandPos>>,
+    reader: KvStoreReader,
+    writer: Arc<Mutex<KvStoreWriter>>,
 }
 
 //数据索引，包含命令所在的偏移量(pos)与长度(len)
+#[derive(Clone, Copy)]
 struct CommandPos {
     pos : u64,      //命令所在偏移位置
     len : u64,      //命令长度
@@ -55,162 +102,346 @@ struct BufWriterWithPos<W: Write + Seek> {
     pos : u64
 }
 
+///只读路径使用的读取器集合。每个`KvStore`克隆拥有自己的一份，互相独立，
+///因此不需要为了读文件而加锁。`removed_gens`与写入端共享：因为压缩现在只挑选
+///死字节比例超标的生成号合并，被删除的生成号不再是一段连续前缀，而是一个
+///离散集合，所以只能用集合记录哪些生成号已经被删除，而不是一个简单的阈值。
+///为了不在每次读取时都去抢这把锁，只有在需要为一个新的生成号打开文件句柄时
+///（缓存未命中）才会检查并清理过期句柄，重复读取同一个生成号完全不涉及加锁。
+struct KvStoreReader {
+    path: Arc<PathBuf>,
+    removed_gens: Arc<Mutex<HashSet<u64>>>,
+    readers: RefCell<HashMap<u64, BufReaderWithPos<File>>>,
+}
+
+impl KvStoreReader {
+    ///读取cmd_pos所指向的命令，并交给`f`处理，f处理完成后即返回其结果
+    fn read_and<F, Res>(&self, cmd_pos: CommandPos, f: F) -> Result<Res>
+    where
+        F: FnOnce(io::Take<&mut BufReaderWithPos<File>>) -> Result<Res>,
+    {
+        let mut readers = self.readers.borrow_mut();
+        if !readers.contains_key(&cmd_pos.gen) {
+            //只有打开新句柄这条冷路径才需要看一眼有没有已经被删除的生成号可以顺手清理掉
+            let removed = self.removed_gens.lock().unwrap();
+            readers.retain(|gen, _| !removed.contains(gen));
+            drop(removed);
+
+            let reader = BufReaderWithPos::new(File::open(load_path(&self.path, cmd_pos.gen))?)?;
+            readers.insert(cmd_pos.gen, reader);
+        }
+        let reader = readers.get_mut(&cmd_pos.gen).unwrap();
+        reader.seek(SeekFrom::Start(cmd_pos.pos))?;
+        let cmd_reader = reader.take(cmd_pos.len);
+        f(cmd_reader)
+    }
+}
+
+impl Clone for KvStoreReader {
+    fn clone(&self) -> KvStoreReader {
+        KvStoreReader {
+            path: Arc::clone(&self.path),
+            removed_gens: Arc::clone(&self.removed_gens),
+            //每个克隆都从空的读取器集合开始，按需为自己的线程打开文件
+            readers: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+///写入器持有的可变状态：当前写入的文件、文件编号与每个生成号的死字节统计。
+///这些状态只会被持有写入器锁的线程修改。
+struct KvStoreWriter {
+    writer : BufWriterWithPos<File>,
+    reader : KvStoreReader,
+    current_gen : u64,
+    gen_stats: HashMap<u64, GenStat>,
+    config: KvStoreConfig,
+    //上一次尝试压缩时没有任何生成号的死字节比例达标。在这种情况下继续拿
+    //total_dead()去试探只会一次次白跑：哪个生成号都没被回收，死字节总数
+    //还在阈值之上，下一次写入又会立刻再触发一次。在有新的死字节产生、
+    //确实可能改变某个生成号的比例之前，直接跳过重试
+    compaction_stalled: bool,
+    path: Arc<PathBuf>,
+    index: Arc<SkipMap<String, CommandPos>>,
+}
+
 
 ///KV存储实现
 impl KvStore {
 
-    ///根据传入path打开对应的KvStore
+    ///根据传入path，使用默认的压缩配置打开对应的KvStore
     pub fn open(path: impl Into<PathBuf>) -> Result<KvStore> {
-        let path = path.into();
-        fs::create_dir_all(&path)?;
+        KvStoreConfig::default().open(path)
+    }
+
+    ///使用`config`指定的压缩策略打开`path`对应的KvStore
+    fn open_with_config(path: impl Into<PathBuf>, config: KvStoreConfig) -> Result<KvStore> {
+        let path = Arc::new(path.into());
+        fs::create_dir_all(&*path)?;
         let mut readers = HashMap::<u64, BufReaderWithPos<File>>::new();
-        let mut index = HashMap::<String, CommandPos>::new();
+        let index = Arc::new(SkipMap::<String, CommandPos>::new());
+        let mut gen_stats = HashMap::<u64, GenStat>::new();
 
         let gen_list = sort_gen_list(&path)?;
-        let mut uncompacted = 0;
-        for &gen in &gen_list {
-            let mut reader = BufReaderWithPos::new(File::open(load_path(&path, gen))?)?;
-            uncompacted += load(gen, &mut reader, &mut index)?;
-            readers.insert(gen, reader);
+        //hint文件记录了上一次compact之后索引的完整快照，如果它仍然有效
+        //（对应的生成号文件还在），就可以跳过它覆盖到的那些日志的重放
+        let hint = read_hint_file(&path).filter(|(hint_gen, _)| gen_list.contains(hint_gen));
+
+        if let Some((hint_gen, entries)) = hint {
+            for (key, cmd_pos) in entries {
+                index.insert(key, cmd_pos);
+            }
+            for &gen in gen_list.iter().filter(|&&gen| gen > hint_gen) {
+                let mut reader = BufReaderWithPos::new(File::open(load_path(&path, gen))?)?;
+                load(&path, gen, &mut reader, &index, &mut gen_stats)?;
+                readers.insert(gen, reader);
+            }
+
+            //重放到这里，index就是"谁还活着"的真相来源。hint快照覆盖到的
+            //生成号里，如果没有任何entry指向它，说明它的数据已经被完整地
+            //压缩进了别的生成号——典型情况是compact()写完hint文件、删除
+            //旧生成号之间崩溃，留下了本该被删掉的残留文件。没有重放就无从
+            //得知这类文件的真实死字节比例，放着不管它们会永远呆在磁盘上、
+            //永远不会被选中参与压缩，所以直接在这里回收掉，而不是假装它是
+            //一个dead字节恒为0的干净生成号
+            let live_gens: HashSet<u64> = index.iter().map(|entry| entry.value().gen).collect();
+            for &gen in gen_list.iter().filter(|&&gen| gen <= hint_gen) {
+                if !live_gens.contains(&gen) {
+                    fs::remove_file(load_path(&path, gen))?;
+                    continue;
+                }
+                let reader = BufReaderWithPos::new(File::open(load_path(&path, gen))?)?;
+                //这个生成号确实还有entry指向它，但它的索引状态已经被hint
+                //快照覆盖，不需要重放日志；没有重放就没法知道hint之前哪些字节
+                //已经死了，只能先用文件大小近似total，dead只保留上面重放更新的
+                //日志时已经累加进来的部分，后续真的发生覆盖/删除时才会慢慢变准
+                let total = reader.reader.get_ref().metadata()?.len();
+                gen_stats.entry(gen).or_default().total = total;
+                readers.insert(gen, reader);
+            }
+        } else {
+            for &gen in &gen_list {
+                let mut reader = BufReaderWithPos::new(File::open(load_path(&path, gen))?)?;
+                load(&path, gen, &mut reader, &index, &mut gen_stats)?;
+                readers.insert(gen, reader);
+            }
         }
         //新的文件编号为文件列表个数+1，如果为空则默认为0
         let current_gen = gen_list.last().unwrap_or(&0) + 1;
 
-        let writer = new_log_file(&path, current_gen, &mut readers)?;
+        let writer = new_log_file(&path, current_gen)?;
+        let removed_gens = Arc::new(Mutex::new(HashSet::new()));
 
-        Ok(KvStore {
-            path,
-            readers,
+        let reader = KvStoreReader {
+            path: Arc::clone(&path),
+            removed_gens,
+            readers: RefCell::new(readers),
+        };
+
+        let writer = KvStoreWriter {
             writer,
-            index,
+            reader: reader.clone(),
             current_gen,
-            uncompacted
+            gen_stats,
+            config,
+            compaction_stalled: false,
+            path: Arc::clone(&path),
+            index: Arc::clone(&index),
+        };
+
+        Ok(KvStore {
+            index,
+            reader,
+            writer: Arc::new(Mutex::new(writer)),
+        })
+    }
+
+    ///返回所有key以`prefix`开头的键值对，按key的字典序排列。
+    ///`index`底层是有序的`SkipMap`，按key顺序遍历不需要额外排序。
+    pub fn scan_prefix(&self, prefix: &str) -> Result<Vec<(String, String)>> {
+        let mut result = Vec::new();
+        for entry in self.index.iter() {
+            if entry.key().starts_with(prefix) {
+                let value = self.read_value(*entry.value())?;
+                result.push((entry.key().clone(), value));
+            }
+        }
+        Ok(result)
+    }
+
+    ///返回key在`[start, end)`区间内的所有键值对，按key的字典序排列
+    pub fn range(&self, start: &str, end: &str) -> Result<Vec<(String, String)>> {
+        let mut result = Vec::new();
+        for entry in self.index.range(start.to_owned()..end.to_owned()) {
+            let value = self.read_value(*entry.value())?;
+            result.push((entry.key().clone(), value));
+        }
+        Ok(result)
+    }
+
+    ///根据索引中的位置信息从对应的日志段里读出value
+    fn read_value(&self, cmd_pos: CommandPos) -> Result<String> {
+        self.reader.read_and(cmd_pos, |mut cmd_reader| {
+            match read_record(&mut cmd_reader)? {
+                Some(LogRecord::Set {value, .. }) => Ok(value),
+                Some(LogRecord::Remove {..}) | None => Err(KvsError::UnexpectedCommandType),
+            }
         })
     }
 
+}
+
+impl KvsEngine for KvStore {
+    ///写入
+    fn set(&self, key: String, value: String) -> Result<()> {
+        self.writer.lock().unwrap().set(key, value)
+    }
+
+    ///查询,如果数据不存在则返回None。
+    ///查询路径不拿写入器的锁：先在无锁的`SkipMap`中找到位置，
+    ///再用自己这个克隆专属的读取器去读，与其他线程的读/写互不干扰。
+    ///只有读取器缓存里没有该生成号的句柄时，才会短暂拿一次`removed_gens`锁。
+    fn get(&self, key: String) -> Result<Option<String>> {
+        if let Some(entry) = self.index.get(&key) {
+            Ok(Some(self.read_value(*entry.value())?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    ///删除
+    fn remove(&self, key: String) -> Result<()> {
+        self.writer.lock().unwrap().remove(key)
+    }
+}
+
+impl KvStoreWriter {
+    ///当前所有生成号累计的死字节总数，用来判断是否需要触发一次压缩
+    fn total_dead(&self) -> u64 {
+        self.gen_stats.values().map(|stat| stat.dead).sum()
+    }
+
     ///写入
-    pub fn set(&mut self, key: String, value: String) -> Result<()> {
-        let cmd = Command::set(key, value);
+    fn set(&mut self, key: String, value: String) -> Result<()> {
         //获取当前写入器的指针所在的偏移位置
         //为何不是写入器对应文件的长度？
         //文件会有一部分覆盖写，实际数据不一定等于文件长度
         let pos = self.writer.pos;
 
-        //将cmd转化为json并写入writer中
-        serde_json::to_writer(&mut self.writer, &cmd)?;
+        //以二进制格式写入一条记录：[op_tag][key_len][value_len][crc32] + key + value
+        write_record(&mut self.writer, &key, Some(&value))?;
         self.writer.flush()?;
 
-        //if let控制流，详情见：
-        //https://kaisery.github.io/trpl-zh-cn/ch06-03-if-let.html
-        if let Command::Set {key, .. } = cmd {
-            //创建索引对象
-            //此时self.writer.pos已经是写入完成后所在的偏移位置
-            let cmd_pos = CommandPos {pos, len: self.writer.pos - pos,  gen: self.current_gen};
-            //加入索引，后面还需要获取索引对象,并计算到压缩数据大小
-            if let Some(old_cmd) = self.index.insert(key, cmd_pos) {
-                self.uncompacted += old_cmd.len;
-            }
-            // if self.uncompacted > COMPACTION_THRESHOLD {}
+        //创建索引对象
+        //此时writer.pos已经是写入完成后所在的偏移位置
+        let len = self.writer.pos - pos;
+        let cmd_pos = CommandPos {pos, len, gen: self.current_gen};
+        //旧位置的字节数归还给它原本所在的那个生成号，而不是当前正在写的这个，
+        //否则旧生成号永远不会被记录为有死字节，选择压缩对象时就看不到它
+        if let Some(old_cmd) = self.index.get(&key) {
+            let old = old_cmd.value();
+            self.gen_stats.entry(old.gen).or_default().dead += old.len;
+            //新产生了死字节，某个生成号的比例可能因此变了，值得重新评估压缩
+            self.compaction_stalled = false;
         }
-        if self.uncompacted > COMPACTION_THRESHOLD {
-            //若当前文件数据大小超过限定大小，执行压缩
+        //加入索引，SkipMap是并发安全的，不需要额外加锁
+        self.index.insert(key, cmd_pos);
+        self.gen_stats.entry(self.current_gen).or_default().total += len;
+
+        if !self.compaction_stalled && self.total_dead() > self.config.compaction_threshold {
+            //若死字节总数超过配置的阈值，执行压缩
             self.compact()?
         }
         Ok(())
     }
 
-    ///查询,如果数据不存在则返回None
-    pub fn get(&mut self, key: String) -> Result<Option<String>> {
-        if let Some(cmd_pos) = self.index.get(&key) {
-            let reader = self.readers.get_mut(&cmd_pos.gen)
-                .expect(format!("不存在的文件编号{}", cmd_pos.gen).as_str());
-            //移动到数据所在位置
-            reader.seek(SeekFrom::Start(cmd_pos.pos))?;
-
-            let cmd_reader = reader.take(cmd_pos.len);
-            if let Command::Set {value, .. } = serde_json::from_reader(cmd_reader)? {
-                Ok(Some(value))
-            } else {
-                Err(KvsError::UnexpectedCommandType)
-            }
-        } else {
-            Ok(None)
-        }
-    }
-
     ///删除
-    pub fn remove(&mut self, key: String) -> Result<()> {
+    fn remove(&mut self, key: String) -> Result<()> {
         if self.index.contains_key(&key) {
-            let cmd = Command::Remove {key};
-            serde_json::to_writer(&mut self.writer, &cmd)?;
-            self.writer.flush();
-            if let Command::Remove {key } = cmd {
-                self.index.remove(&key).expect("key not found");
-            }
+            let pos = self.writer.pos;
+            write_record(&mut self.writer, &key, None)?;
+            self.writer.flush()?;
+            let len = self.writer.pos - pos;
+
+            let old_cmd = self.index.remove(&key).expect("key not found");
+            let old = old_cmd.value();
+            self.gen_stats.entry(old.gen).or_default().dead += old.len;
+            //墓碑记录本身也是一旦写下就注定被压缩丢弃的字节，必须立刻记进自己
+            //所在生成号的死字节里，否则只统计了被它覆盖掉的旧记录，遗漏了它自己
+            let stat = self.gen_stats.entry(self.current_gen).or_default();
+            stat.total += len;
+            stat.dead += len;
+            //新产生了死字节，某个生成号的比例可能因此变了，值得重新评估压缩
+            self.compaction_stalled = false;
+
             Ok(())
         } else {
             Err(KvsError::KeyNotFound)
         }
     }
 
-    ///压缩
-    pub fn compact(&mut self) -> Result<()> {
+    ///压缩。只挑选死字节占比达到`config.compaction_ratio`的生成号参与合并，
+    ///干净的生成号原样留在磁盘上，不会被重写。只有写入器自己的读取器会被用来
+    ///把旧数据拷贝到新的压缩文件里；合并出的生成号被记入`removed_gens`后，
+    ///各个克隆下次需要为某个生成号打开新句柄时，会据此惰性关闭指向这些生成号的过期读取器句柄。
+    fn compact(&mut self) -> Result<()> {
+        let hot_gens: HashSet<u64> = self.gen_stats.iter()
+            .filter(|(_, stat)| stat.total > 0
+                && (stat.dead as f64 / stat.total as f64) >= self.config.compaction_ratio)
+            .map(|(&gen, _)| gen)
+            .collect();
+
+        if hot_gens.is_empty() {
+            //没有任何生成号的死字节比例达标：不挪动current_gen也不新开写入文件，
+            //否则总死字节数仍然会留在阈值之上，下一次写入又会立刻重新走到这里，
+            //于是每次写入都白白新开一个几乎是空的生成号文件。记下这次是空跑，
+            //在有新的死字节出现之前都不用再尝试
+            self.compaction_stalled = true;
+            return Ok(());
+        }
+
         //下一个序号为压缩结果
         let compaction_gen = self.current_gen + 1;
         //下下一个序号为新写入文件，同时修改写入对象
         self.current_gen += 2;
-        //修改写入器
-        self.writer = self.new_log_file(self.current_gen)?;
+        self.writer = new_log_file(&self.path, self.current_gen)?;
 
+        let mut compaction_writer = new_log_file(&self.path, compaction_gen)?;
         let mut new_pos = 0;
-        //根据压缩的目标文件编号创建写入器，并将其加入读取器
-        let mut compaction_writer = self.new_log_file(compaction_gen)?;
-        //遍历当前索引的key
-        for cmd_pos in &mut self.index.values_mut() {
-            //获取当前key的关联文件读取器
-            let reader = self.readers.get_mut(&cmd_pos.gen)
-                .expect(format!("无法找到读取器的文件编号: {}", &cmd_pos.gen).as_str());
-            //将读取器的游标切换到命令的起始位置
-            if reader.pos != cmd_pos.pos {
-                reader.seek(SeekFrom::Start(cmd_pos.pos))?;
+        let mut compacted_total = 0;
+        //只拷贝落在hot_gens里的entry，干净的生成号保持原样
+        for entry in self.index.iter() {
+            if !hot_gens.contains(&entry.value().gen) {
+                continue;
             }
-            //设置读取器读取的数据长度
-            let mut cmd_reader = reader.take(cmd_pos.len);
-            //把命令拷贝到压缩日志写入器中
-            let len = io::copy(&mut cmd_reader, &mut compaction_writer)?;
+            let len = self.reader.read_and(*entry.value(), |mut cmd_reader| {
+                //把命令拷贝到压缩日志写入器中
+                Ok(io::copy(&mut cmd_reader, &mut compaction_writer)?)
+            })?;
             //更新索引中key的命令位置数据
-            *cmd_pos = CommandPos {gen: compaction_gen, pos: new_pos, len };
+            self.index.insert(entry.key().clone(), CommandPos {gen: compaction_gen, pos: new_pos, len});
             new_pos += len;
+            compacted_total += len;
         }
         compaction_writer.flush()?;
 
-        //日志序号是递增的，只需要保留最大的序号的文件即可
-        let stale_gens: Vec<_> = self.readers.keys()
-            .filter(|&&gen| gen < compaction_gen)
-            .cloned().collect();
-        for stale_gen in stale_gens {
-            self.readers.remove(&stale_gen);
-            fs::remove_file(log_path(&self.path, stale_gen))?;
-        }
-        self.uncompacted = 0;
-        Ok(())
-    }
-
-    ///将写入器定位到新的文件编号
-    fn new_log_file(&mut self, gen: u64) -> Result<BufWriterWithPos<File>> {
-        new_log_file(&self.path, gen, &mut self.readers)
-    }
-
-}
+        //压缩后索引快照里既有新合并出的entry也有未参与本次压缩的旧entry，
+        //把它写成hint文件，下次open()就可以跳过这个生成号以前的全部日志重放
+        write_hint_file(&self.path, compaction_gen, &self.index)?;
 
+        for gen in &hot_gens {
+            self.gen_stats.remove(gen);
+        }
+        self.gen_stats.insert(compaction_gen, GenStat { total: compacted_total, dead: 0 });
 
-//写入命令的实现
-impl Command {
-    fn set(key: String, value: String) -> Command {
-        Command::Set {key, value}
-    }
-    fn remove(key: String) -> Command {
-        Command::Remove {key}
+        {
+            let mut removed = self.reader.removed_gens.lock().unwrap();
+            removed.extend(hot_gens.iter().copied());
+        }
+        for gen in hot_gens {
+            fs::remove_file(log_path(&self.path, gen))?;
+        }
+        Ok(())
     }
 }
 
@@ -290,47 +521,58 @@ fn sort_gen_list(path: &Path) -> Result<Vec<u64>> {
     Ok(gen_list)
 }
 
-///根据文件编号、读取器加载数据并将其放入内存索引中。
-fn load(gen: u64, reader:&mut BufReaderWithPos<File>,index: &mut HashMap<String, CommandPos>) -> Result<u64> {
+///根据文件编号、读取器加载数据并将其放入内存索引中，同时把这个生成号以及
+///它覆盖掉的旧生成号的total/dead字节数累加进`gen_stats`。
+///如果在末尾遇到一条不完整或者crc校验失败的记录（说明进程在追加日志时崩溃），
+///就把日志文件截断到最后一条完整记录结束的位置，而不是连带启动一起失败。
+fn load(
+    dir: &Path,
+    gen: u64,
+    reader: &mut BufReaderWithPos<File>,
+    index: &SkipMap<String, CommandPos>,
+    gen_stats: &mut HashMap<u64, GenStat>,
+) -> Result<()> {
     //将reader的指针移动到起点0，即pos开始为0
     let mut pos = reader.seek(SeekFrom::Start(0))?;
-    //根据Command数据序列化格式从reader中读取并反序列化为Command对象,真牛逼
-    let mut stream = Deserializer::from_reader(reader).into_iter::<Command>();
-
-    //压缩后可以保存的字节数据
-    let mut uncompacted = 0;
-    //遍历stream，将结果包装在Some中，
-    //Some中的值必然不为空，即let Some(cmd) = stream.next()
-    //这段语句会先判断strean.next()返回的值是否为空
-    //如果不为空则boolean判断为true，并创建Some(cmd)对象
-    //如果为空则boolean判断为false，则跳出循环
-    while let Some(cmd) = stream.next() {
-        //获取读取一次Command后reader的指针所在的位置
-        //这个指针指向下一个Command的初始位置，因此为new_pos
-        let new_pos = stream.byte_offset() as u64;
-        match cmd? {
-            Command::Set {key, ..} => {
-                //将Set命令在内存中重现，如果添加成功则返回添加成功的对象
-                //并将对象的长度添加到uncompacted中
-                //？为什么不直接用cmd对象呢？
-                //需要将cmd的所有权交给内存索引
-                if let Some(old_cmd) = index.insert(key, CommandPos {gen, pos, len : new_pos - pos}) {
-                    uncompacted += old_cmd.len;
+
+    loop {
+        match read_record(reader)? {
+            Some(LogRecord::Set {key, ..}) => {
+                let new_pos = reader.pos;
+                let len = new_pos - pos;
+                //将Set命令在内存中重现，旧位置的字节数归还给它原本所在的生成号
+                if let Some(old_cmd) = index.get(&key) {
+                    let old = old_cmd.value();
+                    gen_stats.entry(old.gen).or_default().dead += old.len;
                 }
+                index.insert(key, CommandPos {gen, pos, len});
+                gen_stats.entry(gen).or_default().total += len;
+                pos = new_pos;
             },
-            Command::Remove {key, ..} => {
-                //复现Remove命令，将对应的key从索引中删除
+            Some(LogRecord::Remove {key, ..}) => {
+                let new_pos = reader.pos;
+                let len = new_pos - pos;
+                //复现Remove命令，将对应的key从索引中删除，旧位置的字节数归还给它原本所在的生成号
                 if let Some(old_cmd) = index.remove(&key) {
-                    //将Remove的长度添加到字节数据长度中
-                    uncompacted += old_cmd.len;
+                    let old = old_cmd.value();
+                    gen_stats.entry(old.gen).or_default().dead += old.len;
                 }
-                //?
-                uncompacted += new_pos - pos;
+                //墓碑记录自己这部分字节从写下的那一刻起就已经是死字节了
+                let stat = gen_stats.entry(gen).or_default();
+                stat.total += len;
+                stat.dead += len;
+                pos = new_pos;
+            }
+            None => {
+                //剩余字节不足以构成一条完整记录，或者crc不匹配：这是一次崩溃留下的
+                //torn write，把文件截断到上一条完整记录结束的位置(pos)
+                let file = OpenOptions::new().write(true).open(load_path(dir, gen))?;
+                file.set_len(pos)?;
+                break;
             }
         }
-        pos = new_pos;
     }
-    Ok(uncompacted)
+    Ok(())
 }
 
 ///根据存储文件夹路径与文件编号获取该文件编号对应的存储文件
@@ -338,19 +580,204 @@ fn load_path(dir: &Path, gen: u64) -> PathBuf {
     dir.join(format!("{}.log", gen))
 }
 
-///根据path与文件编号创建新的日志文件，并将其加入readers索引中
-fn new_log_file(path: &Path, gen: u64, readers: &mut HashMap<u64, BufReaderWithPos<File>>) -> Result<BufWriterWithPos<File>> {
+///根据path与文件编号创建新的日志文件对应的写入器
+fn new_log_file(path: &Path, gen: u64) -> Result<BufWriterWithPos<File>> {
     let path = load_path(path, gen);
     let writer = BufWriterWithPos::new(OpenOptions::new()
         .create(true)
         .write(true)
         .append(true)
         .open(&path)?)?;
-    //创建阅读器并加入索引
-    readers.insert(gen, BufReaderWithPos::new(File::open(&path)?)?);
     Ok(writer)
 }
 
 fn log_path(dir: &Path, gen: u64) -> PathBuf {
     dir.join(format!("{}.log", gen))
-}
\ No newline at end of file
+}
+
+///hint文件版本号，格式变化时递增，open()遇到不认识的版本会直接当作hint无效
+const HINT_VERSION: u8 = 1;
+
+fn hint_path(dir: &Path) -> PathBuf {
+    dir.join("index.hint")
+}
+
+///把索引当前的完整快照写成hint文件：`[version][hint_gen][count]`，后面跟着
+///`count`条`[key_len][key][gen][pos][len]`记录，最后是覆盖前面所有字节的crc32。
+///`hint_gen`是这份快照覆盖到的生成号——open()只需要重放比它更新的日志。
+fn write_hint_file(dir: &Path, hint_gen: u64, index: &SkipMap<String, CommandPos>) -> Result<()> {
+    let mut buf = Vec::new();
+    buf.write_u8(HINT_VERSION)?;
+    buf.write_u64::<BigEndian>(hint_gen)?;
+    buf.write_u32::<BigEndian>(index.len() as u32)?;
+    for entry in index.iter() {
+        let key_bytes = entry.key().as_bytes();
+        buf.write_u32::<BigEndian>(key_bytes.len() as u32)?;
+        buf.write_all(key_bytes)?;
+        let cmd_pos = entry.value();
+        buf.write_u64::<BigEndian>(cmd_pos.gen)?;
+        buf.write_u64::<BigEndian>(cmd_pos.pos)?;
+        buf.write_u64::<BigEndian>(cmd_pos.len)?;
+    }
+    let crc = crc32fast::hash(&buf);
+    buf.write_u32::<BigEndian>(crc)?;
+    fs::write(hint_path(dir), buf)?;
+    Ok(())
+}
+
+///读取并校验hint文件，返回它覆盖到的生成号以及其中记录的全部(key, CommandPos)。
+///任何版本不匹配、长度不足或者crc校验失败都当作hint不存在处理，调用方会退回
+///完整重放，因此这里只需要返回`None`而不是向上传播错误。
+fn read_hint_file(dir: &Path) -> Option<(u64, Vec<(String, CommandPos)>)> {
+    let data = fs::read(hint_path(dir)).ok()?;
+    if data.len() < 4 {
+        return None;
+    }
+    let (body, crc_bytes) = data.split_at(data.len() - 4);
+    let expected_crc = u32::from_be_bytes([crc_bytes[0], crc_bytes[1], crc_bytes[2], crc_bytes[3]]);
+    if crc32fast::hash(body) != expected_crc {
+        return None;
+    }
+
+    let mut cursor = body;
+    if cursor.read_u8().ok()? != HINT_VERSION {
+        return None;
+    }
+    let hint_gen = cursor.read_u64::<BigEndian>().ok()?;
+    let count = cursor.read_u32::<BigEndian>().ok()?;
+
+    let mut entries = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let key_len = cursor.read_u32::<BigEndian>().ok()? as usize;
+        if cursor.len() < key_len {
+            return None;
+        }
+        let (key_bytes, rest) = cursor.split_at(key_len);
+        let key = String::from_utf8(key_bytes.to_vec()).ok()?;
+        cursor = rest;
+        let gen = cursor.read_u64::<BigEndian>().ok()?;
+        let pos = cursor.read_u64::<BigEndian>().ok()?;
+        let len = cursor.read_u64::<BigEndian>().ok()?;
+        entries.push((key, CommandPos { pos, len, gen }));
+    }
+    Some((hint_gen, entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn temp_dir() -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::SeqCst);
+        let dir = env::temp_dir().join(format!("kvs-test-{}-{}", std::process::id(), n));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    ///回归测试：如果死字节总数超过阈值，但分散在很多key上，没有任何单个
+    ///生成号的死字节比例达到`compaction_ratio`，compact()过去会在提前返回
+    ///之前就已经挪动了current_gen、新开了写入文件，导致死字节总数仍然在
+    ///阈值之上、下一次写入立刻又触发一次同样的空转——每次写入都新开一个
+    ///几乎是空的生成号文件，文件数随写入次数线性增长。用一个几乎不可能
+    ///达标的compaction_ratio复现这种场景：多次重复触发compact()都应该是
+    ///空转，current_gen与磁盘上的文件数都不应该因此发生变化
+    #[test]
+    fn stalled_compaction_does_not_rotate_a_generation_every_write() {
+        let dir = temp_dir();
+        let store = KvStoreConfig::new()
+            .compaction_threshold(1)
+            .compaction_ratio(0.99)
+            .open(dir.clone())
+            .unwrap();
+
+        for key in 0..20u32 {
+            store.set(format!("key{}", key), "value".to_string()).unwrap();
+        }
+
+        let gen_before = store.writer.lock().unwrap().current_gen;
+        let files_before = sort_gen_list(&dir).unwrap();
+
+        //重复覆盖不同的key，每次都会产生新的死字节、越过threshold(1)触发
+        //compact()，但20条记录里只有一小部分被覆盖，比例远够不到0.99，
+        //所以每一次都应该是空转，不应该新建任何生成号文件
+        for key in 0..5u32 {
+            store.set(format!("key{}", key), "overwritten".to_string()).unwrap();
+        }
+
+        let gen_after = store.writer.lock().unwrap().current_gen;
+        let files_after = sort_gen_list(&dir).unwrap();
+
+        assert_eq!(gen_before, gen_after, "a stalled (no-op) compaction must not rotate current_gen");
+        assert_eq!(files_before, files_after, "a stalled (no-op) compaction must not create new generation files");
+
+        for key in 0..5u32 {
+            assert_eq!(store.get(format!("key{}", key)).unwrap(), Some("overwritten".to_string()));
+        }
+        for key in 5..20u32 {
+            assert_eq!(store.get(format!("key{}", key)).unwrap(), Some("value".to_string()));
+        }
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    ///死字节比例真的超标的生成号应当被合并回收，其中的key依然能读到最新值
+    #[test]
+    fn compaction_reclaims_a_hot_generation() {
+        let dir = temp_dir();
+        let store = KvStoreConfig::new()
+            .compaction_threshold(50)
+            .compaction_ratio(0.5)
+            .open(dir.clone())
+            .unwrap();
+
+        for i in 0..50u32 {
+            store.set("key".to_string(), format!("value{}", i)).unwrap();
+        }
+        assert_eq!(store.get("key".to_string()).unwrap(), Some("value49".to_string()));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    ///`open()`通过hint文件跳过重放时，如果某个`gen <= hint_gen`的日志文件
+    ///已经不再被索引中的任何entry引用（典型情况：compact()写完hint文件、
+    ///删除旧生成号之间崩溃，留下了本该被删掉的残留文件），应当在下次打开时
+    ///直接把它回收掉，而不是假装它是一个dead字节恒为0、永远不会被选中
+    ///参与压缩的干净生成号
+    #[test]
+    fn open_reclaims_an_orphaned_generation_left_under_the_hint() {
+        let dir = temp_dir();
+        {
+            let store = KvStoreConfig::new()
+                .compaction_threshold(50)
+                .compaction_ratio(0.5)
+                .open(dir.clone())
+                .unwrap();
+            for i in 0..50u32 {
+                store.set("key".to_string(), format!("value{}", i)).unwrap();
+            }
+            assert_eq!(store.get("key".to_string()).unwrap(), Some("value49".to_string()));
+        }
+
+        //current_gen从1开始，0从来不会是真实用过的生成号，拿它模拟一个
+        //崩溃时遗留下来、早就不被索引引用的残留日志段
+        let orphan = dir.join("0.log");
+        fs::write(&orphan, b"leftover garbage from a crash between hint write and cleanup").unwrap();
+
+        let store = KvStoreConfig::new()
+            .compaction_threshold(50)
+            .compaction_ratio(0.5)
+            .open(dir.clone())
+            .unwrap();
+
+        assert!(
+            !orphan.exists(),
+            "an orphaned generation with no live index entries should be reclaimed on open"
+        );
+        assert_eq!(store.get("key".to_string()).unwrap(), Some("value49".to_string()));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
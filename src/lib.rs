@@ -1,12 +1,21 @@
 mod kv;
 mod error;
+mod engine;
+mod common;
+mod server;
+mod client;
+mod log_format;
+pub mod thread_pool;
 
-pub use kv::KvStore;
+pub use kv::{KvStore, KvStoreConfig};
 pub use error::{KvsError, Result};
+pub use engine::KvsEngine;
+pub use server::KvsServer;
+pub use client::KvsClient;
 
 #[cfg(test)]
 mod tests {
-    use crate::KvStore;
+    use crate::{KvStore, KvsEngine};
     use std::path::{PathBuf, Path};
 
     #[test]
@@ -18,7 +27,7 @@ mod tests {
         path.push("res");
         path.push("database");
 
-        let mut store = KvStore::open(path).expect("数据库不存在");
+        let store = KvStore::open(path).expect("数据库不存在");
         for i in (1..100000).rev() {
             let key : String = "key".to_owned() + &i.to_string();
             let value : String = "value:value：value：value：value：value：value：".to_owned() + &i.to_string();
@@ -0,0 +1,158 @@
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, Read, Write};
+
+const SET_TAG: u8 = 0;
+const REMOVE_TAG: u8 = 1;
+
+///单条日志记录头部的固定长度：op_tag(1) + key_len(4) + value_len(4) + crc32(4)
+pub const HEADER_LEN: u64 = 1 + 4 + 4 + 4;
+
+///从日志中读出来的一条命令
+pub enum LogRecord {
+    Set { key: String, value: String },
+    Remove { key: String },
+}
+
+///把一条命令以 `[op_tag][key_len][value_len][crc32]` + key + value 的格式写入`writer`。
+///`value`为`None`表示这是一条Remove记录，不会写入任何value字节。
+pub fn write_record<W: Write>(writer: &mut W, key: &str, value: Option<&str>) -> io::Result<()> {
+    let tag = if value.is_some() { SET_TAG } else { REMOVE_TAG };
+    let key_bytes = key.as_bytes();
+    let value_bytes = value.map(str::as_bytes).unwrap_or(&[]);
+    let crc = crc32(key_bytes, value_bytes);
+
+    writer.write_u8(tag)?;
+    writer.write_u32::<BigEndian>(key_bytes.len() as u32)?;
+    writer.write_u32::<BigEndian>(value_bytes.len() as u32)?;
+    writer.write_u32::<BigEndian>(crc)?;
+    writer.write_all(key_bytes)?;
+    writer.write_all(value_bytes)?;
+    Ok(())
+}
+
+///从`reader`里读取一条记录。返回`Ok(None)`表示遇到了不完整或者crc校验失败的尾部
+///数据——典型情况是进程在追加日志时崩溃，留下了一条"写了一半"的记录。调用方应当
+///把日志截断到上一条完整记录结束的位置，而不是把这当成致命错误中止启动。
+pub fn read_record<R: Read>(reader: &mut R) -> io::Result<Option<LogRecord>> {
+    let mut header = [0u8; HEADER_LEN as usize];
+    if let Err(e) = reader.read_exact(&mut header) {
+        return if e.kind() == io::ErrorKind::UnexpectedEof {
+            Ok(None)
+        } else {
+            Err(e)
+        };
+    }
+
+    let mut header_reader = &header[..];
+    let tag = header_reader.read_u8()?;
+    let key_len = header_reader.read_u32::<BigEndian>()? as usize;
+    let value_len = header_reader.read_u32::<BigEndian>()? as usize;
+    let crc = header_reader.read_u32::<BigEndian>()?;
+
+    let mut payload = vec![0u8; key_len + value_len];
+    if let Err(e) = reader.read_exact(&mut payload) {
+        return if e.kind() == io::ErrorKind::UnexpectedEof {
+            //payload部分被截断，属于torn write，交由调用方截断日志
+            Ok(None)
+        } else {
+            //其他I/O错误（比如EIO、权限问题）不代表日志损坏，不能当成torn write
+            //处理，否则调用方会把这条记录之后的全部数据当场截断丢弃
+            Err(e)
+        };
+    }
+    if crc32(&payload[..key_len], &payload[key_len..]) != crc {
+        //crc不匹配，同样当作torn write处理
+        return Ok(None);
+    }
+
+    let key = match String::from_utf8(payload[..key_len].to_vec()) {
+        Ok(key) => key,
+        Err(_) => return Ok(None),
+    };
+    match tag {
+        SET_TAG => match String::from_utf8(payload[key_len..].to_vec()) {
+            Ok(value) => Ok(Some(LogRecord::Set { key, value })),
+            Err(_) => Ok(None),
+        },
+        REMOVE_TAG => Ok(Some(LogRecord::Remove { key })),
+        _ => Ok(None),
+    }
+}
+
+fn crc32(key: &[u8], value: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(key);
+    hasher.update(value);
+    hasher.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn round_trips_set_and_remove() {
+        let mut buf = Vec::new();
+        write_record(&mut buf, "key", Some("value")).unwrap();
+        write_record(&mut buf, "key", None).unwrap();
+
+        let mut cursor = Cursor::new(buf);
+        match read_record(&mut cursor).unwrap() {
+            Some(LogRecord::Set { key, value }) => {
+                assert_eq!(key, "key");
+                assert_eq!(value, "value");
+            }
+            _ => panic!("expected a Set record"),
+        }
+        match read_record(&mut cursor).unwrap() {
+            Some(LogRecord::Remove { key }) => assert_eq!(key, "key"),
+            _ => panic!("expected a Remove record"),
+        }
+        assert!(read_record(&mut cursor).unwrap().is_none());
+    }
+
+    ///末尾被截断的记录（崩溃时写了一半）应该被当成torn write，返回`Ok(None)`
+    ///而不是报错
+    #[test]
+    fn truncated_tail_is_reported_as_none_not_an_error() {
+        let mut buf = Vec::new();
+        write_record(&mut buf, "key", Some("value")).unwrap();
+        buf.truncate(buf.len() - 2);
+
+        let mut cursor = Cursor::new(buf);
+        assert!(read_record(&mut cursor).unwrap().is_none());
+    }
+
+    ///一个真正的I/O错误（这里用一个提前返回Err的Read实现模拟）不应该被
+    ///当成torn write吞掉，必须原样传播给调用方
+    #[test]
+    fn real_io_error_is_not_swallowed_as_torn_write() {
+        struct FlakyReader {
+            good: Cursor<Vec<u8>>,
+            remaining_good_bytes: usize,
+        }
+        impl Read for FlakyReader {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.remaining_good_bytes == 0 {
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, "boom"));
+                }
+                let n = buf.len().min(self.remaining_good_bytes);
+                let read = self.good.read(&mut buf[..n])?;
+                self.remaining_good_bytes -= read;
+                Ok(read)
+            }
+        }
+
+        let mut buf = Vec::new();
+        write_record(&mut buf, "key", Some("value")).unwrap();
+        let mut reader = FlakyReader {
+            good: Cursor::new(buf),
+            remaining_good_bytes: HEADER_LEN as usize,
+        };
+        match read_record(&mut reader) {
+            Err(e) => assert_eq!(e.kind(), io::ErrorKind::PermissionDenied),
+            Ok(_) => panic!("expected the underlying I/O error to propagate"),
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use crate::common::{read_frame, write_frame, Request, Response};
+use crate::thread_pool::ThreadPool;
+use crate::{KvsEngine, Result};
+
+///基于TCP的KvStore服务端。每接受一个连接就把处理工作交给线程池里的一个线程，
+///线程读取一帧请求、调用`engine`、写回对应的响应帧后就关闭这个连接——
+///每个连接只处理一次请求，不会在同一个连接上循环等待下一帧。
+pub struct KvsServer<E: KvsEngine, P: ThreadPool> {
+    engine: E,
+    pool: P,
+}
+
+impl<E: KvsEngine, P: ThreadPool> KvsServer<E, P> {
+    ///使用指定的存储引擎与线程池创建服务端
+    pub fn new(engine: E, pool: P) -> KvsServer<E, P> {
+        KvsServer { engine, pool }
+    }
+
+    ///在`addr`上监听，持续接受连接直到出现致命错误
+    pub fn run<A: ToSocketAddrs>(&mut self, addr: A) -> Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        for stream in listener.incoming() {
+            let engine = self.engine.clone();
+            match stream {
+                Ok(stream) => {
+                    self.pool.spawn(move || {
+                        if let Err(e) = serve(engine, stream) {
+                            eprintln!("Error on serving client: {}", e);
+                        }
+                    });
+                }
+                Err(e) => eprintln!("Connection failed: {}", e),
+            }
+        }
+        Ok(())
+    }
+}
+
+///处理单个连接：读取它唯一的一帧请求，根据类型调用引擎，写回响应帧后返回，
+///连接随即被调用方（`run`里spawn出的闭包）丢弃并关闭
+fn serve<E: KvsEngine>(engine: E, mut stream: TcpStream) -> Result<()> {
+    let req: Request = read_frame(&mut stream)?;
+    let response = match req {
+        Request::Set { key, value } => match engine.set(key, value) {
+            Ok(()) => Response::Ok(None),
+            Err(e) => Response::Err(e.to_string()),
+        },
+        Request::Get { key } => match engine.get(key) {
+            Ok(value) => Response::Ok(value),
+            Err(e) => Response::Err(e.to_string()),
+        },
+        Request::Remove { key } => match engine.remove(key) {
+            Ok(()) => Response::Ok(None),
+            Err(e) => Response::Err(e.to_string()),
+        },
+    };
+    write_frame(&mut stream, &response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::KvsClient;
+    use crate::thread_pool::NaiveThreadPool;
+    use crate::KvStore;
+    use std::env;
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    fn temp_dir() -> std::path::PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::SeqCst);
+        let dir = env::temp_dir().join(format!("kvs-server-test-{}-{}", std::process::id(), n));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    ///跑一个真实的server+client，走一遍set/get/remove，确认连接每次请求
+    ///都重新拨号也能正常工作
+    #[test]
+    fn client_server_roundtrip_over_loopback() {
+        let dir = temp_dir();
+        let engine = KvStore::open(dir.clone()).unwrap();
+
+        //先在127.0.0.1:0上绑一次拿到系统分配的空闲端口，再把监听交给server.run
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let pool = NaiveThreadPool::new(4).unwrap();
+        let mut server = KvsServer::new(engine, pool);
+        thread::spawn(move || server.run(addr).unwrap());
+        thread::sleep(Duration::from_millis(100));
+
+        let client = KvsClient::connect(addr).unwrap();
+        client.set("key".to_string(), "value".to_string()).unwrap();
+        assert_eq!(
+            client.get("key".to_string()).unwrap(),
+            Some("value".to_string())
+        );
+        client.remove("key".to_string()).unwrap();
+        assert_eq!(client.get("key".to_string()).unwrap(), None);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
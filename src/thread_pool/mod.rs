@@ -0,0 +1,21 @@
+use crate::Result;
+
+mod naive;
+mod shared_queue;
+
+pub use naive::NaiveThreadPool;
+pub use shared_queue::SharedQueueThreadPool;
+
+///一个可以执行任务的线程池。不同实现在"如何调度任务到线程"上各有取舍，
+///但对外都表现为：创建指定数量的线程，并能把一个任务丢进去异步执行。
+pub trait ThreadPool {
+    ///创建一个拥有`threads`个线程的线程池
+    fn new(threads: u32) -> Result<Self>
+    where
+        Self: Sized;
+
+    ///把`job`交给线程池中的某个线程执行，调用本身不会阻塞
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static;
+}
@@ -0,0 +1,21 @@
+use std::thread;
+
+use super::ThreadPool;
+use crate::Result;
+
+///最朴素的线程池实现：根本不维护线程，每来一个任务就开一个新的OS线程去跑，
+///跑完线程自然退出。`threads`参数被忽略，仅仅是为了满足`ThreadPool`的签名。
+pub struct NaiveThreadPool;
+
+impl ThreadPool for NaiveThreadPool {
+    fn new(_threads: u32) -> Result<NaiveThreadPool> {
+        Ok(NaiveThreadPool)
+    }
+
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        thread::spawn(job);
+    }
+}
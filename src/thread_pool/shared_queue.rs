@@ -0,0 +1,103 @@
+use std::thread;
+
+use crossbeam::channel::{self, Receiver, Sender};
+
+use super::ThreadPool;
+use crate::{KvsError, Result};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+///预先开`threads`个工作线程，所有线程共享同一个任务队列。
+///提交任务只是把它丢进`crossbeam`的channel，由空闲的工作线程取走执行。
+pub struct SharedQueueThreadPool {
+    tx: Sender<Job>,
+}
+
+impl ThreadPool for SharedQueueThreadPool {
+    fn new(threads: u32) -> Result<SharedQueueThreadPool> {
+        if threads == 0 {
+            return Err(KvsError::StringError(
+                "SharedQueueThreadPool requires at least one thread".to_string(),
+            ));
+        }
+        let (tx, rx) = channel::unbounded::<Job>();
+        for _ in 0..threads {
+            spawn_worker(rx.clone());
+        }
+        Ok(SharedQueueThreadPool { tx })
+    }
+
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.tx
+            .send(Box::new(job))
+            .expect("The shared queue thread pool has no running worker");
+    }
+}
+
+///工作线程的哨兵：只要它的线程还活着就一直持有`Receiver`的一份克隆。
+///`Drop`时检查当前线程是否正在展开panic——如果是，说明某个任务把线程带崩了，
+///立刻补一个新的工作线程顶上，线程池的总容量因此不会因为任务panic而减少。
+struct Sentinel(Receiver<Job>);
+
+impl Drop for Sentinel {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            spawn_worker(self.0.clone());
+        }
+    }
+}
+
+fn spawn_worker(rx: Receiver<Job>) {
+    thread::Builder::new()
+        .spawn(move || {
+            let sentinel = Sentinel(rx);
+            while let Ok(job) = sentinel.0.recv() {
+                job();
+            }
+        })
+        .expect("Failed to spawn a worker thread");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::thread_pool::ThreadPool;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    ///`new(0)`不应该静默返回一个没有任何工作线程的池子——那样第一次
+    ///`spawn`会在`expect`上panic。既然签名返回`Result`，就应该在构造时
+    ///直接报错
+    #[test]
+    fn new_with_zero_threads_is_rejected() {
+        assert!(SharedQueueThreadPool::new(0).is_err());
+    }
+
+    ///一个job panic之后，Sentinel的Drop应该立刻补一个新的工作线程顶上，
+    ///池子剩余容量不会因为一次panic就永久减少
+    #[test]
+    fn panicking_job_does_not_shrink_pool() {
+        let pool = SharedQueueThreadPool::new(4).unwrap();
+        let (panicked_tx, panicked_rx) = mpsc::channel();
+        pool.spawn(move || {
+            panicked_tx.send(()).unwrap();
+            panic!("deliberate panic to exercise sentinel respawn");
+        });
+        panicked_rx.recv_timeout(Duration::from_secs(5)).expect("panicking job never ran");
+
+        //给sentinel一点时间完成Drop里的respawn
+        thread::sleep(Duration::from_millis(200));
+
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..4 {
+            let tx = tx.clone();
+            pool.spawn(move || tx.send(()).unwrap());
+        }
+        for _ in 0..4 {
+            rx.recv_timeout(Duration::from_secs(5)).expect("pool lost a worker after a job panicked");
+        }
+    }
+}